@@ -1,22 +1,76 @@
+mod srp;
+
 use std::collections::HashMap;
 use anyhow::format_err;
+use num_bigint::BigUint;
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{Serialize, Deserialize};
+use srp::SrpClient;
+use thiserror::Error;
+
+/// Errors surfaced by session persistence that callers may want to match on,
+/// as opposed to the opaque [`anyhow::Error`] used elsewhere in this crate.
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("persisted session has expired")]
+    SessionExpired,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PersistedSession {
+    cookies: Vec<Cookie>,
+    services: HashMap<String, Service>,
+    trust_token: Option<String>,
+    region: Region,
+}
+
+/// The account region, which determines which Apple ID/iCloud hosts to talk to.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum Region {
+    #[default]
+    Global,
+    ChinaMainland,
+}
+
+impl Region {
+    fn icloud_host(&self) -> &'static str {
+        match self {
+            Region::Global => "https://www.icloud.com",
+            Region::ChinaMainland => "https://www.icloud.com.cn",
+        }
+    }
+
+    fn setup_url(&self) -> String {
+        match self {
+            Region::Global => "https://setup.icloud.com/setup/ws/1".to_string(),
+            Region::ChinaMainland => "https://setup.icloud.com.cn/setup/ws/1".to_string(),
+        }
+    }
+
+    fn idmsa_url(&self) -> &'static str {
+        match self {
+            Region::Global => "https://idmsa.apple.com/appleauth/auth",
+            Region::ChinaMainland => "https://idmsa.apple.com.cn/appleauth/auth",
+        }
+    }
+}
 
 pub struct ICloudClient {
     client: reqwest::Client,
     services: HashMap<String, Service>,
     cookies: Vec<Cookie>,
+    trust_token: Option<String>,
+    region: Region,
 }
 pub struct HideMyEmailManager {
     icloud: ICloudClient,
     cookie: String,
 }
 impl ICloudClient {
-    pub fn new(cookies: &[Cookie]) -> ICloudClient {
+    pub fn new(cookies: &[Cookie], region: Region) -> ICloudClient {
         let mut headers = HeaderMap::new();
-        headers.insert("Origin", HeaderValue::from_static("https://www.icloud.com"));
-        headers.insert("Referer", HeaderValue::from_static("https://www.icloud.com/"));
+        headers.insert("Origin", HeaderValue::from_str(region.icloud_host()).unwrap());
+        headers.insert("Referer", HeaderValue::from_str(&format!("{}/", region.icloud_host())).unwrap());
         headers.insert("Accept", HeaderValue::from_static("*/*"));
 
         let cookie = cookies.iter().map(|c| format!("{}={}", c.name, c.value)).collect::<Vec<String>>().join("; ");
@@ -32,15 +86,124 @@ impl ICloudClient {
             client,
             services: HashMap::new(),
             cookies: cookies.to_vec(),
+            trust_token: None,
+            region,
         }
     }
 
-    fn setup_url() -> &'static str {
-        "https://setup.icloud.com/setup/ws/1"
+    fn setup_url(&self) -> String {
+        self.region.setup_url()
+    }
+
+    /// The long-lived 2FA trust token captured during [`ICloudClient::login`], if any.
+    /// Persist this and pass it through on future runs to skip two-factor prompts.
+    pub fn trust_token(&self) -> Option<&str> {
+        self.trust_token.as_deref()
+    }
+
+    /// Signs in with an Apple ID and password, performing the full SRP-6a
+    /// handshake against the region's idmsa host instead of requiring
+    /// pre-extracted browser cookies. If the account has HSA2 two-factor
+    /// enabled, `provide_two_factor_code` is called to obtain the 6-digit
+    /// code sent to the user's trusted device. Pass a `trust_token` captured
+    /// from a previous [`ICloudClient::trust_token`] to suppress that 2FA
+    /// prompt on this run.
+    pub async fn login<F>(apple_id: &str, password: &str, region: Region, trust_token: Option<&str>, mut provide_two_factor_code: F) -> anyhow::Result<ICloudClient>
+    where
+        F: FnMut() -> anyhow::Result<String>,
+    {
+        let auth_client = reqwest::ClientBuilder::new()
+            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/121.0.0.0 Safari/537.36")
+            .cookie_store(true)
+            .build()?;
+
+        let srp_client = SrpClient::new();
+        let init_payload = SrpInitPayload {
+            account_name: apple_id.to_string(),
+            a: hex::encode(srp_client.a_pub.to_bytes_be()),
+            protocols: vec!["s2k".to_string()],
+            trust_tokens: trust_token.map(|t| vec![t.to_string()]).unwrap_or_default(),
+        };
+
+        let resp = auth_client.post(format!("{}/signin/init", region.idmsa_url())).json(&init_payload).send().await?;
+        let status = resp.status();
+        let text = resp.text().await?;
+        if status.is_client_error() || status.is_server_error() {
+            return Err(format_err!("Failed to initialise sign-in | Status: {} | Response: {}", status, text));
+        }
+        let init: SrpInitResponse = serde_json::from_str(&text)?;
+
+        let salt = base64::decode(&init.salt)?;
+        let b_pub = BigUint::parse_bytes(init.b.as_bytes(), 16).ok_or_else(|| format_err!("Invalid server public value"))?;
+        let x = srp::derive_x(password, &salt, init.iteration);
+        let (m1, session_key) = srp_client.process_challenge(apple_id, &salt, &b_pub, &x)?;
+        let m2 = srp_client.client_evidence_2(&m1, &session_key);
+
+        let complete_payload = SrpCompletePayload {
+            account_name: apple_id.to_string(),
+            m1: hex::encode(m1.to_bytes_be()),
+            m2: hex::encode(m2.to_bytes_be()),
+            c: init.c.clone(),
+        };
+
+        let resp = auth_client.post(format!("{}/signin/complete?isRememberMeEnabled=true", region.idmsa_url())).json(&complete_payload).send().await?;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let text = resp.text().await?;
+
+        let mut trust_token = trust_token.map(|t| t.to_string());
+        if status == reqwest::StatusCode::CONFLICT {
+            let session_id = headers.get("X-Apple-Id-Session-Id").ok_or_else(|| format_err!("Missing session id in two-factor challenge"))?.to_str()?.to_string();
+            let scnt = headers.get("scnt").ok_or_else(|| format_err!("Missing scnt in two-factor challenge"))?.to_str()?.to_string();
+
+            let code = provide_two_factor_code()?;
+            let verify_payload = TwoFactorPayload { security_code: SecurityCode { code } };
+            let resp = auth_client.post(format!("{}/verify/trusteddevice/securitycode", region.idmsa_url()))
+                .header("X-Apple-Id-Session-Id", &session_id)
+                .header("scnt", &scnt)
+                .json(&verify_payload)
+                .send().await?;
+            let verify_status = resp.status();
+            let verify_text = resp.text().await?;
+            if verify_status.is_client_error() || verify_status.is_server_error() {
+                return Err(format_err!("Failed to verify two-factor code | Status: {} | Response: {}", verify_status, verify_text));
+            }
+
+            let resp = auth_client.post(format!("{}/2sv/trust", region.idmsa_url()))
+                .header("X-Apple-Id-Session-Id", &session_id)
+                .header("scnt", &scnt)
+                .send().await?;
+            let trust_status = resp.status();
+            if trust_status.is_client_error() || trust_status.is_server_error() {
+                return Err(format_err!("Failed to trust this device | Status: {}", trust_status));
+            }
+            trust_token = resp.headers().get("X-Apple-TwoSV-Trust-Token").and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        } else if status.is_client_error() || status.is_server_error() {
+            return Err(format_err!("Failed to complete sign-in | Status: {} | Response: {}", status, text));
+        }
+
+        let ds_web_auth_token = headers.get("X-Apple-Session-Token").ok_or_else(|| format_err!("Missing session token in sign-in response"))?.to_str()?.to_string();
+
+        let account_login_payload = AccountLoginPayload {
+            ds_web_auth_token,
+            trust_token: trust_token.clone(),
+        };
+        let resp = auth_client.post(format!("{}/accountLogin", region.setup_url())).json(&account_login_payload).send().await?;
+        let status = resp.status();
+        let cookies = resp.cookies().map(|c| Cookie { name: c.name().to_string(), value: c.value().to_string() }).collect::<Vec<Cookie>>();
+        let text = resp.text().await?;
+        if status.is_client_error() || status.is_server_error() {
+            return Err(format_err!("Failed to establish iCloud session | Status: {} | Response: {}", status, text));
+        }
+
+        let mut icloud = ICloudClient::new(&cookies, region);
+        icloud.trust_token = trust_token;
+        icloud.validate().await?;
+        Ok(icloud)
     }
 
     pub async fn validate(&mut self) -> anyhow::Result<()> {
-        let url = format!("{}/validate", ICloudClient::setup_url());
+        let url = format!("{}/validate", self.setup_url());
         let resp = self.client.post(url).send().await?;
 
         let status = resp.status();
@@ -50,6 +213,9 @@ impl ICloudClient {
         }).collect::<Vec<Cookie>>();
 
         let text = resp.text().await?;
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(SessionError::SessionExpired.into());
+        }
         if status.is_client_error() || status.is_server_error() {
             return Err(format_err!("Failed to send request | Status: {} | Response: {}", status, text));
         }
@@ -77,6 +243,45 @@ impl ICloudClient {
 
         Ok(())
     }
+
+    /// Serializes the cookies, resolved webservice map and trust token to
+    /// `path` so the session can be restored with [`ICloudClient::load_session`]
+    /// without re-authenticating.
+    pub fn save_session(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let persisted = PersistedSession {
+            cookies: self.cookies.clone(),
+            services: self.services.clone(),
+            trust_token: self.trust_token.clone(),
+            region: self.region,
+        };
+
+        std::fs::write(path, serde_json::to_string_pretty(&persisted)?)?;
+        Ok(())
+    }
+
+    /// Rebuilds an `ICloudClient` from a file written by [`ICloudClient::save_session`]
+    /// without a network round trip, then re-validates the session. If the
+    /// session has expired (Apple's `/validate` endpoint returns 401/403),
+    /// returns [`SessionError::SessionExpired`] so the caller knows to fall
+    /// back to [`ICloudClient::login`]; any other validation failure (network
+    /// error, unexpected response shape, etc.) is propagated with its
+    /// original cause intact so it isn't misreported as "needs re-login."
+    pub async fn load_session(path: impl AsRef<std::path::Path>) -> anyhow::Result<ICloudClient> {
+        let text = std::fs::read_to_string(path)?;
+        let persisted: PersistedSession = serde_json::from_str(&text)?;
+
+        let mut icloud = ICloudClient::new(&persisted.cookies, persisted.region);
+        icloud.services = persisted.services;
+        icloud.trust_token = persisted.trust_token;
+
+        match icloud.validate().await {
+            Ok(()) => Ok(icloud),
+            Err(e) => match e.downcast_ref::<SessionError>() {
+                Some(SessionError::SessionExpired) => Err(e),
+                None => Err(e.context("failed to validate restored session")),
+            },
+        }
+    }
 }
 impl HideMyEmailManager {
     pub fn from(icloud: ICloudClient) -> HideMyEmailManager {
@@ -176,6 +381,52 @@ impl HideMyEmailManager {
         self.claim(&hme, label, note).await?;
         Ok(hme)
     }
+    pub async fn deactivate(&self, anonymous_id: &str) -> anyhow::Result<()> {
+        let payload = HMEAnonymousIdPayload { anonymous_id: anonymous_id.into() };
+        self.send_action("/v1/hme/deactivate", &payload).await
+    }
+    pub async fn reactivate(&self, anonymous_id: &str) -> anyhow::Result<()> {
+        let payload = HMEAnonymousIdPayload { anonymous_id: anonymous_id.into() };
+        self.send_action("/v1/hme/reactivate", &payload).await
+    }
+    pub async fn delete(&self, anonymous_id: &str) -> anyhow::Result<()> {
+        let payload = HMEAnonymousIdPayload { anonymous_id: anonymous_id.into() };
+        self.send_action("/v1/hme/delete", &payload).await
+    }
+    pub async fn update_metadata(&self, anonymous_id: &str, label: &str, note: &str) -> anyhow::Result<()> {
+        let payload = HMEUpdateMetadataPayload {
+            anonymous_id: anonymous_id.into(),
+            label: label.into(),
+            note: note.into(),
+        };
+        self.send_action("/v1/hme/updateMetaData", &payload).await
+    }
+    pub async fn update_forward_to(&self, forward_to_email: &str) -> anyhow::Result<()> {
+        let payload = HMEUpdateForwardToPayload { forward_to_email: forward_to_email.into() };
+        self.send_action("/v1/hme/updateForwardTo", &payload).await
+    }
+
+    async fn send_action(&self, path: &str, payload: &impl Serialize) -> anyhow::Result<()> {
+        let base = match self.base_url() {
+            Some(t) => t,
+            None => return Err(format_err!("Missing Base URL"))
+        };
+
+        let resp = self.icloud.client.post(format!("{}{}", base, path)).header("Cookie", &self.cookie).json(payload).send().await?;
+
+        let status = resp.status();
+        let text = resp.text().await?;
+        if status.is_server_error() || status.is_client_error() {
+            return Err(format_err!("Failed to send request | Status: {} | Response: {}", status, text));
+        }
+
+        let body: HMEActionResponse = serde_json::from_str(&text)?;
+        if !body.success {
+            return Err(format_err!("Hide my email action at {} was not successful", path));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Default, Debug, Clone)]
@@ -184,29 +435,67 @@ pub struct Cookie {
     value: String,
 }
 
+/// A cookie-pair failed to parse per RFC 6265 (either it has no `=`
+/// separator or an empty name). Carries the byte offset of that pair within
+/// the original cookie-string.
 #[derive(Debug, Eq, PartialEq)]
-struct ParseCookieError<'a>(&'a str);
+struct ParseCookieError(usize);
+
+impl ParseCookieError {
+    pub fn offset(&self) -> usize {
+        self.0
+    }
+}
 
 impl Cookie {
-    // Only supports name=value;
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Cookie { name: name.into(), value: value.into() }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Parses a `Cookie:`-style header value (`name=value; name2=value2`)
+    /// per RFC 6265's cookie-pair grammar: optional whitespace around each
+    /// pair is trimmed, a single pair of surrounding double quotes is
+    /// stripped from the value, and a pair with no `=` or an empty name is
+    /// rejected with the byte offset of that pair.
     fn from_str(s: &str) -> Result<Vec<Self>, ParseCookieError> {
         let mut cookies = Vec::new();
-        let splt = s.split("; ");
-        for cookie in splt {
-            match cookie.split_once("=") {
-                Some((k, v)) => {
+        let mut offset = 0;
+        // A single trailing separator (as real `document.cookie` dumps sometimes have) just
+        // leaves a blank final pair; drop it rather than treat it as malformed.
+        let s = s.strip_suffix(';').unwrap_or(s);
+        for pair in s.split(';') {
+            let after_leading_ws = pair.trim_start_matches([' ', '\t']);
+            let pair_offset = offset + (pair.len() - after_leading_ws.len());
+            let trimmed = after_leading_ws.trim_end_matches([' ', '\t']);
+
+            match trimmed.split_once('=') {
+                Some((name, value)) if !name.is_empty() => {
+                    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                        Some(unquoted) => unquoted,
+                        None => value,
+                    };
                     cookies.push(Self {
-                        name: k.to_string(),
-                        value: v.to_string(),
+                        name: name.to_string(),
+                        value: value.to_string(),
                     })
                 },
-                None => return Err(ParseCookieError(cookie))
+                _ => return Err(ParseCookieError(pair_offset)),
             }
+
+            offset += pair.len() + 1;
         }
         Ok(cookies)
     }
 }
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct Service {
     url: Option<String>,
     status: Option<String>,
@@ -286,18 +575,80 @@ struct HMEClaimPayload {
     label: String,
     note: String,
 }
+#[derive(Deserialize, Debug)]
+struct HMEActionResponse {
+    success: bool,
+    timestamp: u64,
+}
+#[derive(Serialize, Debug)]
+struct HMEAnonymousIdPayload {
+    #[serde(rename = "anonymousId")]
+    anonymous_id: String,
+}
+#[derive(Serialize, Debug)]
+struct HMEUpdateMetadataPayload {
+    #[serde(rename = "anonymousId")]
+    anonymous_id: String,
+    label: String,
+    note: String,
+}
+#[derive(Serialize, Debug)]
+struct HMEUpdateForwardToPayload {
+    #[serde(rename = "forwardToEmail")]
+    forward_to_email: String,
+}
+#[derive(Serialize, Debug)]
+struct SrpInitPayload {
+    #[serde(rename = "accountName")]
+    account_name: String,
+    a: String,
+    protocols: Vec<String>,
+    #[serde(rename = "trustTokens", skip_serializing_if = "Vec::is_empty")]
+    trust_tokens: Vec<String>,
+}
+#[derive(Deserialize, Debug)]
+struct SrpInitResponse {
+    salt: String,
+    b: String,
+    c: String,
+    iteration: u32,
+}
+#[derive(Serialize, Debug)]
+struct SrpCompletePayload {
+    #[serde(rename = "accountName")]
+    account_name: String,
+    m1: String,
+    m2: String,
+    c: String,
+}
+#[derive(Serialize, Debug)]
+struct SecurityCode {
+    code: String,
+}
+#[derive(Serialize, Debug)]
+struct TwoFactorPayload {
+    #[serde(rename = "securityCode")]
+    security_code: SecurityCode,
+}
+#[derive(Serialize, Debug)]
+struct AccountLoginPayload {
+    #[serde(rename = "dsWebAuthToken")]
+    ds_web_auth_token: String,
+    #[serde(rename = "trustToken", skip_serializing_if = "Option::is_none")]
+    trust_token: Option<String>,
+}
 
 #[cfg(test)]
 mod tests {
     use std::env;
-    use crate::{Cookie, HideMyEmailManager, ICloudClient, ParseCookieError};
+    use crate::{Cookie, HideMyEmailManager, ICloudClient, ParseCookieError, PersistedSession, Region};
 
     #[tokio::test]
     async fn generate_hme_and_claim() {
         let cookies = env::var("COOKIE").unwrap();
         let cookies = Cookie::from_str(&cookies).unwrap();
 
-        let mut icloud = ICloudClient::new(&cookies);
+        let mut icloud = ICloudClient::new(&cookies, Region::Global);
         icloud.validate().await.unwrap();
         let manager = HideMyEmailManager::from(icloud);
 
@@ -310,7 +661,7 @@ mod tests {
         let cookies = env::var("COOKIE").unwrap();
         let cookies = Cookie::from_str(&cookies).unwrap();
 
-        let mut icloud = ICloudClient::new(&cookies);
+        let mut icloud = ICloudClient::new(&cookies, Region::Global);
         icloud.validate().await.unwrap();
         let manager = HideMyEmailManager::from(icloud);
 
@@ -326,44 +677,86 @@ mod tests {
         let cookie = "";
         let result = Cookie::from_str(cookie);
         assert_eq!(result.is_err(), true);
-        assert_eq!(result.unwrap_err(), ParseCookieError(""));
+        assert_eq!(result.unwrap_err(), ParseCookieError(0));
     }
 
     #[test]
     fn cookie_from_str_valid() {
-        let cookie = "x-APPLE-WEBAUTH-PCS-Documents=\"abc123==\"; X-APPLE-WEBAUTH-PCS-Photos=\"123+kv2==\"; X-APPLE-WEBAUTH-PCS-Cloudkit=\"1x73==233==\"; =banana";
+        let cookie = "x-APPLE-WEBAUTH-PCS-Documents=\"abc123==\"; X-APPLE-WEBAUTH-PCS-Photos=\"123+kv2==\"; X-APPLE-WEBAUTH-PCS-Cloudkit=\"1x73==233==\"";
         let test_output = vec![
             Cookie {
                 name: "x-APPLE-WEBAUTH-PCS-Documents".to_string(),
-                value: "\"abc123==\"".to_string(),
+                value: "abc123==".to_string(),
             },
             Cookie {
                 name: "X-APPLE-WEBAUTH-PCS-Photos".to_string(),
-                value: "\"123+kv2==\"".to_string(),
+                value: "123+kv2==".to_string(),
             },
             Cookie {
                 name: "X-APPLE-WEBAUTH-PCS-Cloudkit".to_string(),
-                value: "\"1x73==233==\"".to_string(),
+                value: "1x73==233==".to_string(),
             },
-            Cookie {
-                name: "".to_string(),
-                value: "banana".to_string(),
-            }
         ];
         let result: Result<Vec<Cookie>, ParseCookieError> = Cookie::from_str(cookie);
         assert_eq!(result.is_ok(), true);
         let result = result.unwrap();
-        assert_eq!(result.len(), 4);
+        assert_eq!(result.len(), 3);
         for (a, b) in test_output.into_iter().zip(result) {
             assert_eq!(a, b);
         }
     }
 
+    #[test]
+    fn cookie_from_str_blank_name_is_rejected() {
+        let cookie = "task=4343; =banana";
+        let result = Cookie::from_str(cookie);
+        assert_eq!(result.is_err(), true);
+        assert_eq!(result.unwrap_err(), ParseCookieError(11));
+    }
+
+    #[test]
+    fn cookie_from_str_trims_surrounding_whitespace() {
+        let cookie = "task=4343;  client=abs31 ";
+        let result = Cookie::from_str(cookie).unwrap();
+        assert_eq!(result, vec![
+            Cookie { name: "task".to_string(), value: "4343".to_string() },
+            Cookie { name: "client".to_string(), value: "abs31".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn cookie_from_str_ignores_a_single_trailing_separator() {
+        let cookie = "a=1; b=2;";
+        let result = Cookie::from_str(cookie).unwrap();
+        assert_eq!(result, vec![
+            Cookie { name: "a".to_string(), value: "1".to_string() },
+            Cookie { name: "b".to_string(), value: "2".to_string() },
+        ]);
+    }
+
     #[test]
     fn cookie_from_str_error() {
         let cookie = "task=4343; session17373; client=abs31";
         let result = Cookie::from_str(cookie);
         assert_eq!(result.is_err(), true);
-        assert_eq!(result.unwrap_err(), ParseCookieError("session17373"));
+        assert_eq!(result.unwrap_err(), ParseCookieError(11));
+    }
+
+    #[test]
+    fn save_session_round_trips_through_json() {
+        let cookies = Cookie::from_str("a=1; b=2").unwrap();
+        let icloud = ICloudClient::new(&cookies, Region::Global);
+
+        let path = env::temp_dir().join("hide_my_email_save_session_round_trip_test.json");
+        icloud.save_session(&path).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let persisted: PersistedSession = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(persisted.cookies, cookies);
+        assert_eq!(persisted.region, Region::Global);
+        assert_eq!(persisted.trust_token, None);
+        assert_eq!(persisted.services.len(), 0);
     }
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+//! SRP-6a client-side math for Apple's `s2k` sign-in protocol.
+use anyhow::format_err;
+use num_bigint::BigUint;
+use num_traits::Zero;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// RFC 5054 2048-bit safe prime group.
+const N_HEX: &str = "AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050A37329CBB4A099ED8193E0757767A13DD52312AB4B03310DCD7F48A9DA04FD50E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9962F0B93B855F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773BCA97B43A23FB801676BD207A436C6481F1D2B9078717461A5B9D32E688F87748544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52FB3786160279004E57AE6AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB694B5C803D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73";
+const G: u64 = 2;
+
+fn n() -> BigUint {
+    BigUint::parse_bytes(N_HEX.as_bytes(), 16).unwrap()
+}
+
+fn byte_len() -> usize {
+    (n().bits() as usize + 7) / 8
+}
+
+/// Left-pads `x` with zero bytes to the width of `N`, as required before hashing.
+fn pad(x: &BigUint) -> Vec<u8> {
+    let mut bytes = x.to_bytes_be();
+    let len = byte_len();
+    if bytes.len() < len {
+        let mut padded = vec![0u8; len - bytes.len()];
+        padded.extend_from_slice(&bytes);
+        bytes = padded;
+    }
+    bytes
+}
+
+/// Derives the SRP password verifier exponent `x` from the account password,
+/// Apple's `s2k` scheme hashes the password with SHA-256 before the PBKDF2 step.
+pub fn derive_x(password: &str, salt: &[u8], iterations: u32) -> BigUint {
+    let password_hash = Sha256::digest(password.as_bytes());
+    let mut out = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(&password_hash, salt, iterations, &mut out);
+    BigUint::from_bytes_be(&out)
+}
+
+pub struct SrpClient {
+    a: BigUint,
+    pub a_pub: BigUint,
+}
+
+impl SrpClient {
+    /// Generates a fresh ephemeral private/public key pair (`a`, `A = g^a mod N`).
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut buf = [0u8; 32];
+        rng.fill_bytes(&mut buf);
+        let a = BigUint::from_bytes_be(&buf);
+        Self::from_private(a)
+    }
+
+    /// Builds a client with a fixed private exponent `a`, so tests can drive
+    /// the handshake against a reproducible key pair instead of a random one.
+    fn from_private(a: BigUint) -> Self {
+        let a_pub = BigUint::from(G).modpow(&a, &n());
+        SrpClient { a, a_pub }
+    }
+
+    /// Processes the server's challenge (`salt`, `B`) and returns the client
+    /// evidence message `M1` together with the derived shared session key.
+    ///
+    /// Per RFC 5054 §3, rejects a degenerate `B` (`B mod N == 0`) or a
+    /// resulting `u == 0` rather than completing the handshake, since either
+    /// would let a malicious server force a predictable session key.
+    pub fn process_challenge(&self, identity: &str, salt: &[u8], b_pub: &BigUint, x: &BigUint) -> anyhow::Result<(BigUint, Vec<u8>)> {
+        let n = n();
+        let g = BigUint::from(G);
+
+        if (b_pub % &n).is_zero() {
+            return Err(format_err!("Server public value B is degenerate (B mod N == 0)"));
+        }
+
+        let k = {
+            let mut hasher = Sha256::new();
+            hasher.update(pad(&n));
+            hasher.update(pad(&g));
+            BigUint::from_bytes_be(&hasher.finalize())
+        };
+        let u = {
+            let mut hasher = Sha256::new();
+            hasher.update(pad(&self.a_pub));
+            hasher.update(pad(b_pub));
+            BigUint::from_bytes_be(&hasher.finalize())
+        };
+        if u.is_zero() {
+            return Err(format_err!("Scrambling parameter u is degenerate (u == 0)"));
+        }
+
+        let gx = g.modpow(x, &n);
+        let kgx = (&k * &gx) % &n;
+        let base = (&n + b_pub - &kgx) % &n;
+        let exp = &self.a + (&u * x);
+        let s = base.modpow(&exp, &n);
+        let session_key = Sha256::digest(pad(&s)).to_vec();
+
+        let hn_xor_hg: Vec<u8> = Sha256::digest(pad(&n)).iter().zip(Sha256::digest(pad(&g)).iter()).map(|(a, b)| a ^ b).collect();
+        let hi = Sha256::digest(identity.as_bytes());
+
+        let mut m1_hasher = Sha256::new();
+        m1_hasher.update(&hn_xor_hg);
+        m1_hasher.update(hi);
+        m1_hasher.update(salt);
+        m1_hasher.update(pad(&self.a_pub));
+        m1_hasher.update(pad(b_pub));
+        m1_hasher.update(&session_key);
+        let m1 = BigUint::from_bytes_be(&m1_hasher.finalize());
+
+        Ok((m1, session_key))
+    }
+
+    /// Computes the server evidence message `M2` the client expects back, used
+    /// here as the `m2` field Apple's `/signin/complete` endpoint requires.
+    pub fn client_evidence_2(&self, m1: &BigUint, session_key: &[u8]) -> BigUint {
+        let mut hasher = Sha256::new();
+        hasher.update(pad(&self.a_pub));
+        hasher.update(pad(m1));
+        hasher.update(session_key);
+        BigUint::from_bytes_be(&hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_x_is_deterministic_for_a_fixed_salt_and_iteration_count() {
+        let x = derive_x("hunter2", b"fixedsalt1234567", 1000);
+        assert_eq!(hex::encode(x.to_bytes_be()), "7469c12d1770b78f3ceaabdb8c42fe899c017eefedc5ab62c4acc325e03c46c7");
+    }
+
+    #[test]
+    fn process_challenge_matches_a_fixed_vector() {
+        let identity = "test@example.com";
+        let salt = b"fixedsalt1234567";
+        let x = derive_x("hunter2", salt, 1000);
+        let client = SrpClient::from_private(BigUint::from(7u64));
+        let b_pub = BigUint::parse_bytes(b"92c67f0f47504918b8626e691b1c33b1b86fa9d517f7cb94a74d460c7e8050b716b9f259b967d41badaf515033ea928464deedd09a40a9cf03e3c38d924d855dfa4df27ae72593e4e151da8f2fbcf7a121c44c6d9543957eb8f9c4a423a4dcc152a6390baaf387aee892fbafe65ef8ca3e54448279ad92aeda63fdb689f7cd213ca21eab2cff55062f839e0a9ff54b8f7d54485150ab9d6f868e50269626cd53b851407a5aefbfc21060b423cf3f0ae9836032952fd1a1a7ab9dbfe04f156f1a309e0b6b19dc985f65b509640fc61007a5e8aea605fb6841d08b744043866dc49026561a3cbd79d7db023ab63216fdd06e544f247cd0e393396d3cc287b3f30a", 16).unwrap();
+
+        let (m1, session_key) = client.process_challenge(identity, salt, &b_pub, &x).unwrap();
+        let m2 = client.client_evidence_2(&m1, &session_key);
+
+        assert_eq!(hex::encode(m1.to_bytes_be()), "0b7569cdf7d405ddfbf936ab2897a2151f099d1c6c91a58122aa1e7dc26a6756");
+        assert_eq!(hex::encode(&session_key), "4544461780f516eefe0deccbb45f99b687d48f912969dac0fdfd7d4882101aa4");
+        assert_eq!(hex::encode(m2.to_bytes_be()), "dc0df1c201c01fe0162d64f48b3922f6ff78ee81a573e68b1cd31de9f13ce6a5");
+    }
+
+    #[test]
+    fn process_challenge_rejects_a_zero_b_pub() {
+        let client = SrpClient::from_private(BigUint::from(7u64));
+        let x = derive_x("hunter2", b"fixedsalt1234567", 1000);
+
+        // B == N is congruent to 0 mod N, the degenerate case RFC 5054 requires rejecting.
+        let result = client.process_challenge("test@example.com", b"fixedsalt1234567", &n(), &x);
+        assert!(result.is_err());
+    }
+}